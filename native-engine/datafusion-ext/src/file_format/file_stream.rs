@@ -21,25 +21,32 @@
 //! Note: Most traits here need to be marked `Sync + Send` to be
 //! compliant with the `SendableRecordBatchStream` trait.
 
+use std::any::Any;
 use std::collections::VecDeque;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
 
-use crate::file_format::{ObjectMeta, PartitionedFile};
+use crate::file_format::schema_adapter::SchemaAdapter;
+use crate::file_format::{FileScanConfig, ObjectMeta, PartitionColumnProjector, PartitionedFile};
 use datafusion::arrow::datatypes::SchemaRef;
-use datafusion::arrow::{error::Result as ArrowResult, record_batch::RecordBatch};
+use datafusion::arrow::{
+    error::{ArrowError, Result as ArrowResult},
+    record_batch::RecordBatch,
+};
 use datafusion::common::ScalarValue;
 use datafusion::datasource::listing::FileRange;
 use datafusion::error::Result;
 use datafusion::execution::context::TaskContext;
-use datafusion::physical_plan::metrics::BaselineMetrics;
+use datafusion::physical_plan::metrics::{
+    BaselineMetrics, Count, ExecutionPlanMetricsSet, MetricBuilder,
+};
 use datafusion::physical_plan::RecordBatchStream;
 use futures::future::BoxFuture;
 use futures::stream::BoxStream;
 use futures::{ready, FutureExt, Stream, StreamExt};
+use log::warn;
 
-use crate::file_format::{FileScanConfig, PartitionColumnProjector};
 use crate::util::fs::FsProvider;
 
 /// A fallible future that resolves to a stream of [`RecordBatch`]
@@ -52,12 +59,49 @@ pub trait FormatReader: Unpin {
         fs_provider: Arc<FsProvider>,
         file: ObjectMeta,
         range: Option<FileRange>,
+        extensions: Option<Arc<dyn Any + Send + Sync>>,
     ) -> ReaderFuture;
 }
 
+/// How many files ahead of the one currently being scanned `FileStream`
+/// keeps in flight by default; see [`FileStream::with_prefetch_depth`].
+const DEFAULT_PREFETCH_DEPTH: usize = 1;
+
+/// How `FileStream` should react when a file fails to open, or fails on the
+/// first batch it produces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileStreamErrorPolicy {
+    /// Abort the whole stream, as before. The default.
+    #[default]
+    FailFast,
+    /// Log the failure, bump the skipped-file counter, and move on to the
+    /// next file as if this one were empty.
+    SkipFile,
+    /// Like `SkipFile`, but emit one empty batch of `projected_schema` for
+    /// the file instead of silently producing nothing.
+    NullPad,
+}
+
+/// The state of a [`FormatReader::open`] call that has been kicked off ahead
+/// of time, driven forward by [`FileStream::drive_prefetch_queue`] until it
+/// resolves.
+enum PendingOpenState {
+    /// Still waiting on IO; polled on every [`FileStream::poll_inner`] call.
+    Pending(ReaderFuture),
+    /// Resolved, possibly before this file's turn to be scanned came up.
+    Ready(Result<BoxStream<'static, ArrowResult<RecordBatch>>>),
+}
+
+/// A [`FormatReader::open`] call that has been kicked off ahead of time and
+/// not yet transitioned into [`FileStreamState::Open`].
+struct PendingOpen {
+    state: PendingOpenState,
+    partition_values: Vec<ScalarValue>,
+}
+
 /// A stream that iterates record batch by record batch, file over file.
 pub struct FileStream<F: FormatReader> {
-    /// An iterator over input files.
+    /// An iterator over input files not yet handed to [`FormatReader::open`].
     file_iter: VecDeque<PartitionedFile>,
     /// The stream schema (file schema including partition columns and after
     /// projection).
@@ -77,6 +121,17 @@ pub struct FileStream<F: FormatReader> {
     state: FileStreamState,
     /// Baseline metrics
     baseline_metrics: BaselineMetrics,
+    /// In-flight [`FormatReader::open`] calls for files after the current
+    /// one, kept up to `prefetch_depth` deep so their IO overlaps with the
+    /// current file's `Scan`. See [`Self::fill_prefetch_queue`] and
+    /// [`Self::drive_prefetch_queue`].
+    prefetch_queue: VecDeque<PendingOpen>,
+    /// How many files ahead to keep open()'d at once.
+    prefetch_depth: usize,
+    /// What to do when a file fails to open or fails on its first batch.
+    error_policy: FileStreamErrorPolicy,
+    /// Number of files skipped or null-padded by `error_policy`.
+    skipped_files: Count,
 }
 
 enum FileStreamState {
@@ -97,6 +152,14 @@ enum FileStreamState {
         partition_values: Vec<ScalarValue>,
         /// The reader instance
         reader: BoxStream<'static, ArrowResult<RecordBatch>>,
+        /// Reconciles this file's schema with `projected_schema`, in case the
+        /// file was written by an older/newer version of the table
+        schema_adapter: SchemaAdapter,
+        /// Whether `reader` has not yet produced its first item. The error
+        /// policy only applies to a failure here or in `Open`, since by the
+        /// time later batches are read, earlier ones for this file have
+        /// already been handed to the caller.
+        first_batch: bool,
     },
     /// Encountered an error
     Error,
@@ -111,7 +174,7 @@ impl<F: FormatReader> FileStream<F> {
         partition: usize,
         _context: Arc<TaskContext>,
         file_reader: F,
-        baseline_metrics: BaselineMetrics,
+        metrics: &ExecutionPlanMetricsSet,
     ) -> Result<Self> {
         let (projected_schema, _) = config.project();
         let pc_projector = PartitionColumnProjector::new(
@@ -129,31 +192,155 @@ impl<F: FormatReader> FileStream<F> {
             pc_projector,
             fs_provider,
             state: FileStreamState::Idle,
-            baseline_metrics,
+            baseline_metrics: BaselineMetrics::new(metrics, partition),
+            prefetch_queue: VecDeque::new(),
+            prefetch_depth: DEFAULT_PREFETCH_DEPTH,
+            error_policy: FileStreamErrorPolicy::default(),
+            // Registered the same way as `baseline_metrics` so it surfaces
+            // through the plan's `MetricsSet` (e.g. in `EXPLAIN ANALYZE`),
+            // not just via the `skipped_files()` getter.
+            skipped_files: MetricBuilder::new(metrics).counter("num_skipped_files", partition),
         })
     }
 
+    /// Keep up to `prefetch_depth` files' worth of [`FormatReader::open`]
+    /// calls in flight ahead of the one currently being scanned, so their
+    /// open IO overlaps with scanning the current file. Must be at least 1.
+    pub fn with_prefetch_depth(mut self, prefetch_depth: usize) -> Self {
+        self.prefetch_depth = prefetch_depth.max(1);
+        self
+    }
+
+    /// Set how the stream should react to a file that fails to open or
+    /// fails on its first batch, instead of always aborting the stream.
+    pub fn with_error_policy(mut self, error_policy: FileStreamErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Number of files this stream has skipped or null-padded due to
+    /// `error_policy`.
+    pub fn skipped_files(&self) -> usize {
+        self.skipped_files.value()
+    }
+
+    /// Apply `self.error_policy` to a file that failed to open, or whose
+    /// reader failed before producing a single batch. Returns the value
+    /// `poll_inner` should return immediately, or `None` if it should keep
+    /// looping (the state has already been advanced to `Idle`).
+    fn handle_unreadable_file(
+        &mut self,
+        error: ArrowError,
+    ) -> Option<Poll<Option<ArrowResult<RecordBatch>>>> {
+        match self.error_policy {
+            FileStreamErrorPolicy::FailFast => {
+                self.state = FileStreamState::Error;
+                self.cancel_prefetched();
+                Some(Poll::Ready(Some(Err(error))))
+            }
+            FileStreamErrorPolicy::SkipFile => {
+                warn!("skipping unreadable file: {error}");
+                self.skipped_files.add(1);
+                self.state = FileStreamState::Idle;
+                None
+            }
+            FileStreamErrorPolicy::NullPad => {
+                warn!("null-padding unreadable file: {error}");
+                self.skipped_files.add(1);
+                self.state = FileStreamState::Idle;
+                Some(Poll::Ready(Some(Ok(RecordBatch::new_empty(
+                    self.projected_schema.clone(),
+                )))))
+            }
+        }
+    }
+
+    /// Start opening files from `file_iter` until `prefetch_queue` holds
+    /// `prefetch_depth` in-flight futures (or `file_iter` is exhausted).
+    fn fill_prefetch_queue(&mut self) {
+        while self.prefetch_queue.len() < self.prefetch_depth {
+            let file = match self.file_iter.pop_front() {
+                Some(file) => file,
+                None => break,
+            };
+
+            let future = self.file_reader.open(
+                self.fs_provider.clone(),
+                file.object_meta,
+                file.range,
+                file.extensions.clone(),
+            );
+
+            self.prefetch_queue.push_back(PendingOpen {
+                state: PendingOpenState::Pending(future),
+                partition_values: file.partition_values,
+            });
+        }
+    }
+
+    /// Poll every still-`Pending` entry of `prefetch_queue` once, so their IO
+    /// makes progress regardless of which one is next up to become the
+    /// current file's `Open`/`Scan` state. This is what actually lets a
+    /// prefetched file's open overlap with the current file's `Scan`: a
+    /// future only runs when polled, so without this every prefetched future
+    /// would sit untouched until it was popped into `Open`.
+    fn drive_prefetch_queue(&mut self, cx: &mut Context<'_>) {
+        for pending in self.prefetch_queue.iter_mut() {
+            if let PendingOpenState::Pending(future) = &mut pending.state {
+                if let Poll::Ready(result) = future.poll_unpin(cx) {
+                    pending.state = PendingOpenState::Ready(result);
+                }
+            }
+        }
+    }
+
+    /// Drop any outstanding prefetched opens and stop pulling more files, for
+    /// use once the stream has hit [`FileStreamState::Error`] or
+    /// [`FileStreamState::Limit`].
+    fn cancel_prefetched(&mut self) {
+        self.prefetch_queue.clear();
+        self.file_iter.clear();
+    }
+
     fn poll_inner(
         &mut self,
         cx: &mut Context<'_>,
     ) -> Poll<Option<ArrowResult<RecordBatch>>> {
         loop {
+            self.fill_prefetch_queue();
+            self.drive_prefetch_queue(cx);
+
             match &mut self.state {
                 FileStreamState::Idle => {
-                    let file = match self.file_iter.pop_front() {
-                        Some(file) => file,
+                    let pending = match self.prefetch_queue.pop_front() {
+                        Some(pending) => pending,
                         None => return Poll::Ready(None),
                     };
 
-                    let future = self.file_reader.open(
-                        self.fs_provider.clone(),
-                        file.object_meta,
-                        file.range,
-                    );
-
-                    self.state = FileStreamState::Open {
-                        future,
-                        partition_values: file.partition_values,
+                    match pending.state {
+                        // Already resolved while another file was being
+                        // scanned: skip `Open` and its extra poll entirely.
+                        PendingOpenState::Ready(Ok(reader)) => {
+                            self.state = FileStreamState::Scan {
+                                partition_values: pending.partition_values,
+                                reader,
+                                schema_adapter: SchemaAdapter::new(
+                                    self.pc_projector.file_schema(),
+                                ),
+                                first_batch: true,
+                            };
+                        }
+                        PendingOpenState::Ready(Err(e)) => {
+                            if let Some(poll) = self.handle_unreadable_file(e.into()) {
+                                return poll;
+                            }
+                        }
+                        PendingOpenState::Pending(future) => {
+                            self.state = FileStreamState::Open {
+                                future,
+                                partition_values: pending.partition_values,
+                            }
+                        }
                     }
                 }
                 FileStreamState::Open {
@@ -164,19 +351,31 @@ impl<F: FormatReader> FileStream<F> {
                         self.state = FileStreamState::Scan {
                             partition_values: std::mem::take(partition_values),
                             reader,
+                            schema_adapter: SchemaAdapter::new(self.pc_projector.file_schema()),
+                            first_batch: true,
                         };
                     }
                     Err(e) => {
-                        self.state = FileStreamState::Error;
-                        return Poll::Ready(Some(Err(e.into())));
+                        if let Some(poll) = self.handle_unreadable_file(e.into()) {
+                            return poll;
+                        }
                     }
                 },
                 FileStreamState::Scan {
                     reader,
                     partition_values,
+                    schema_adapter,
+                    first_batch,
                 } => match ready!(reader.poll_next_unpin(cx)) {
+                    Some(Err(e)) if *first_batch => {
+                        if let Some(poll) = self.handle_unreadable_file(e) {
+                            return poll;
+                        }
+                    }
                     Some(result) => {
+                        *first_batch = false;
                         let result = result
+                            .and_then(|b| schema_adapter.adapt(b))
                             .and_then(|b| self.pc_projector.project(b, partition_values))
                             .map(|batch| match &mut self.remain {
                                 Some(remain) => {
@@ -196,6 +395,9 @@ impl<F: FormatReader> FileStream<F> {
                         if result.is_err() {
                             self.state = FileStreamState::Error
                         }
+                        if matches!(self.state, FileStreamState::Error | FileStreamState::Limit) {
+                            self.cancel_prefetched();
+                        }
 
                         return Poll::Ready(Some(result));
                     }
@@ -226,3 +428,306 @@ impl<F: FormatReader> RecordBatchStream for FileStream<F> {
         self.projected_schema.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use chrono::{TimeZone, Utc};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::common::Statistics;
+    use object_store::memory::InMemory;
+    use object_store::path::Path;
+
+    use super::*;
+
+    fn test_object_meta(path: &str) -> ObjectMeta {
+        ObjectMeta {
+            location: Path::from(path),
+            last_modified: Utc.timestamp_opt(0, 0).unwrap(),
+            size: 0,
+        }
+    }
+
+    /// How a [`TestReader`] should behave for a given path's `open()` call.
+    #[derive(Clone, Copy)]
+    enum ReaderMode {
+        /// Resolves on first poll to a stream yielding one empty batch, then
+        /// ending.
+        Batch,
+        /// Resolves on first poll to a stream whose only item is an error.
+        ScanError,
+        /// Resolves on first poll to a stream that yields one good empty
+        /// batch, then an error.
+        BatchThenError,
+        /// Never resolves; every poll just bumps that path's counter.
+        Pending,
+    }
+
+    /// A [`FormatReader`] whose `open()` behavior is scripted per path by
+    /// `modes`, and which counts how many times each path's open future has
+    /// been polled, so tests can observe prefetching actually happening.
+    struct TestReader {
+        open_polls: Arc<Mutex<HashMap<String, Arc<AtomicUsize>>>>,
+        modes: HashMap<String, ReaderMode>,
+        schema: SchemaRef,
+    }
+
+    impl TestReader {
+        fn new(schema: SchemaRef, modes: HashMap<String, ReaderMode>) -> Self {
+            Self {
+                open_polls: Arc::new(Mutex::new(HashMap::new())),
+                modes,
+                schema,
+            }
+        }
+    }
+
+    fn poll_count(open_polls: &Mutex<HashMap<String, Arc<AtomicUsize>>>, path: &str) -> usize {
+        open_polls
+            .lock()
+            .unwrap()
+            .get(path)
+            .map(|counter| counter.load(Ordering::SeqCst))
+            .unwrap_or(0)
+    }
+
+    impl FormatReader for TestReader {
+        fn open(
+            &self,
+            _fs_provider: Arc<FsProvider>,
+            file: ObjectMeta,
+            _range: Option<FileRange>,
+            _extensions: Option<Arc<dyn Any + Send + Sync>>,
+        ) -> ReaderFuture {
+            let path = file.location.to_string();
+            let counter = self
+                .open_polls
+                .lock()
+                .unwrap()
+                .entry(path.clone())
+                .or_insert_with(|| Arc::new(AtomicUsize::new(0)))
+                .clone();
+            let mode = self
+                .modes
+                .get(&path)
+                .copied()
+                .unwrap_or(ReaderMode::Pending);
+            let schema = self.schema.clone();
+
+            Box::pin(futures::future::poll_fn(move |_cx| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                match mode {
+                    ReaderMode::Pending => Poll::Pending,
+                    ReaderMode::Batch => {
+                        let batch = RecordBatch::new_empty(schema.clone());
+                        let stream: BoxStream<'static, ArrowResult<RecordBatch>> =
+                            Box::pin(futures::stream::once(async move { Ok(batch) }));
+                        Poll::Ready(Ok(stream))
+                    }
+                    ReaderMode::ScanError => {
+                        let stream: BoxStream<'static, ArrowResult<RecordBatch>> =
+                            Box::pin(futures::stream::once(async {
+                                Err(ArrowError::ComputeError("boom".to_string()))
+                            }));
+                        Poll::Ready(Ok(stream))
+                    }
+                    ReaderMode::BatchThenError => {
+                        let batch = RecordBatch::new_empty(schema.clone());
+                        let stream: BoxStream<'static, ArrowResult<RecordBatch>> = Box::pin(
+                            futures::stream::iter(vec![
+                                Ok(batch),
+                                Err(ArrowError::ComputeError("boom".to_string())),
+                            ]),
+                        );
+                        Poll::Ready(Ok(stream))
+                    }
+                }
+            }))
+        }
+    }
+
+    fn test_stream(
+        modes: HashMap<String, ReaderMode>,
+        limit: Option<usize>,
+    ) -> (FileStream<TestReader>, Arc<Mutex<HashMap<String, Arc<AtomicUsize>>>>) {
+        test_stream_with_policy(modes, limit, FileStreamErrorPolicy::FailFast)
+    }
+
+    fn test_stream_with_policy(
+        modes: HashMap<String, ReaderMode>,
+        limit: Option<usize>,
+        error_policy: FileStreamErrorPolicy,
+    ) -> (FileStream<TestReader>, Arc<Mutex<HashMap<String, Arc<AtomicUsize>>>>) {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, true)]));
+        let config = FileScanConfig {
+            file_schema: schema.clone(),
+            file_groups: vec![vec![
+                PartitionedFile::new(test_object_meta("file0")),
+                PartitionedFile::new(test_object_meta("file1")),
+            ]],
+            statistics: Statistics::default(),
+            projection: None,
+            limit,
+            table_partition_cols: vec![],
+        };
+        let fs_provider = Arc::new(FsProvider::new(Arc::new(InMemory::new())));
+        let reader = TestReader::new(schema, modes);
+        let open_polls = reader.open_polls.clone();
+        let metrics = ExecutionPlanMetricsSet::new();
+
+        let stream = FileStream::new(
+            fs_provider,
+            &config,
+            0,
+            Arc::new(TaskContext::default()),
+            reader,
+            &metrics,
+        )
+        .unwrap()
+        .with_error_policy(error_policy);
+
+        (stream, open_polls)
+    }
+
+    fn poll_once(stream: &mut FileStream<TestReader>) -> Poll<Option<ArrowResult<RecordBatch>>> {
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        Pin::new(stream).poll_next(&mut cx)
+    }
+
+    #[test]
+    fn prefetch_queue_drives_queued_opens_before_they_are_popped() {
+        let modes = HashMap::from([
+            ("file0".to_string(), ReaderMode::Batch),
+            ("file1".to_string(), ReaderMode::Pending),
+        ]);
+        let (mut stream, open_polls) = test_stream(modes, None);
+
+        match poll_once(&mut stream) {
+            Poll::Ready(Some(Ok(_batch))) => {}
+            _ => panic!("expected file0's batch on the first poll"),
+        }
+
+        let file1_polls = poll_count(&open_polls, "file1");
+        assert!(
+            file1_polls >= 1,
+            "file1's open should already have been polled by \
+             drive_prefetch_queue while file0 was being scanned, not left \
+             untouched until it was popped; got {file1_polls} polls"
+        );
+    }
+
+    #[test]
+    fn error_drops_queued_prefetched_opens() {
+        let modes = HashMap::from([
+            ("file0".to_string(), ReaderMode::ScanError),
+            ("file1".to_string(), ReaderMode::Pending),
+        ]);
+        let (mut stream, open_polls) = test_stream(modes, None);
+
+        match poll_once(&mut stream) {
+            Poll::Ready(Some(Err(_))) => {}
+            _ => panic!("expected file0's scan error on the first poll"),
+        }
+
+        assert!(
+            poll_count(&open_polls, "file1") >= 1,
+            "file1 should have been prefetched before the error cancelled it"
+        );
+        assert!(
+            stream.prefetch_queue.is_empty(),
+            "cancel_prefetched should have dropped the queued file1 open"
+        );
+        assert!(
+            stream.file_iter.is_empty(),
+            "cancel_prefetched should have stopped pulling more files"
+        );
+    }
+
+    #[test]
+    fn reaching_limit_drops_queued_prefetched_opens() {
+        let modes = HashMap::from([
+            ("file0".to_string(), ReaderMode::Batch),
+            ("file1".to_string(), ReaderMode::Pending),
+        ]);
+        let (mut stream, open_polls) = test_stream(modes, Some(0));
+
+        match poll_once(&mut stream) {
+            Poll::Ready(Some(Ok(_batch))) => {}
+            _ => panic!("expected file0's (empty, limit-truncated) batch"),
+        }
+
+        assert!(
+            poll_count(&open_polls, "file1") >= 1,
+            "file1 should have been prefetched before the limit cancelled it"
+        );
+        assert!(
+            stream.prefetch_queue.is_empty(),
+            "cancel_prefetched should have dropped the queued file1 open"
+        );
+        assert!(
+            stream.file_iter.is_empty(),
+            "cancel_prefetched should have stopped pulling more files"
+        );
+    }
+
+    #[test]
+    fn skip_file_advances_to_next_file_and_counts_it() {
+        let modes = HashMap::from([
+            ("file0".to_string(), ReaderMode::ScanError),
+            ("file1".to_string(), ReaderMode::Batch),
+        ]);
+        let (mut stream, _open_polls) =
+            test_stream_with_policy(modes, None, FileStreamErrorPolicy::SkipFile);
+
+        match poll_once(&mut stream) {
+            Poll::Ready(Some(Ok(_batch))) => {}
+            _ => panic!("expected file1's batch after file0 was skipped"),
+        }
+        assert_eq!(stream.skipped_files(), 1);
+    }
+
+    #[test]
+    fn null_pad_emits_an_empty_batch_of_the_projected_schema() {
+        let modes = HashMap::from([
+            ("file0".to_string(), ReaderMode::ScanError),
+            ("file1".to_string(), ReaderMode::Pending),
+        ]);
+        let (mut stream, _open_polls) =
+            test_stream_with_policy(modes, None, FileStreamErrorPolicy::NullPad);
+
+        let batch = match poll_once(&mut stream) {
+            Poll::Ready(Some(Ok(batch))) => batch,
+            _ => panic!("expected a null-padded batch in place of file0"),
+        };
+        assert_eq!(batch.num_rows(), 0);
+        assert_eq!(batch.schema(), stream.projected_schema);
+        assert_eq!(stream.skipped_files(), 1);
+    }
+
+    #[test]
+    fn error_after_first_batch_hard_fails_regardless_of_policy() {
+        let modes = HashMap::from([
+            ("file0".to_string(), ReaderMode::BatchThenError),
+            ("file1".to_string(), ReaderMode::Pending),
+        ]);
+        let (mut stream, _open_polls) =
+            test_stream_with_policy(modes, None, FileStreamErrorPolicy::SkipFile);
+
+        match poll_once(&mut stream) {
+            Poll::Ready(Some(Ok(_batch))) => {}
+            _ => panic!("expected file0's first (good) batch"),
+        }
+        // The second item from the same file's reader errors; the error
+        // policy only covers `open()` and the first batch, so this must
+        // hard-fail the stream even though the policy is SkipFile.
+        match poll_once(&mut stream) {
+            Poll::Ready(Some(Err(_))) => {}
+            _ => panic!("expected file0's second-batch error to hard-fail the stream"),
+        }
+        assert_eq!(stream.skipped_files(), 0);
+    }
+}
@@ -0,0 +1,216 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Common types shared by the native file format readers (parquet, orc, ...).
+
+pub mod file_stream;
+pub mod schema_adapter;
+
+use std::sync::Arc;
+
+use datafusion::arrow::datatypes::{Field, Schema, SchemaRef};
+use datafusion::arrow::error::Result as ArrowResult;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::{ColumnStatistics, ScalarValue, Statistics};
+use datafusion::datasource::listing::FileRange;
+use datafusion::error::DataFusionError;
+
+pub use object_store::ObjectMeta;
+
+/// A single file that should be scanned by a partition, along with the range
+/// of the file (if only part of it is to be scanned) and the values of the
+/// partition columns it implicitly carries.
+#[derive(Debug, Clone)]
+pub struct PartitionedFile {
+    /// Path, size and modification metadata of the file, as reported by the
+    /// object store.
+    pub object_meta: ObjectMeta,
+    /// Values of the partition columns for this file, in the order declared
+    /// by [`FileScanConfig::table_partition_cols`].
+    pub partition_values: Vec<ScalarValue>,
+    /// An optional byte range restricting which part of the file to scan.
+    pub range: Option<FileRange>,
+    /// Opaque, format-specific payload a caller can attach to this file so a
+    /// [`file_stream::FormatReader`] can reuse it instead of recomputing it
+    /// from the object store (e.g. a pre-parsed Parquet footer).
+    pub extensions: Option<Arc<dyn std::any::Any + Send + Sync>>,
+}
+
+impl PartitionedFile {
+    /// Create a `PartitionedFile` covering the whole object, with no
+    /// partition values and no precomputed extensions.
+    pub fn new(object_meta: ObjectMeta) -> Self {
+        Self {
+            object_meta,
+            partition_values: vec![],
+            range: None,
+            extensions: None,
+        }
+    }
+}
+
+/// Configuration for a scan over a set of files, already split into
+/// per-partition file groups.
+#[derive(Debug, Clone)]
+pub struct FileScanConfig {
+    /// Schema of the files themselves, before projection or partition
+    /// columns are added.
+    pub file_schema: SchemaRef,
+    /// Files to scan, grouped by partition.
+    pub file_groups: Vec<Vec<PartitionedFile>>,
+    /// Estimated overall statistics of the files, taking `limit` into
+    /// account.
+    pub statistics: Statistics,
+    /// Columns on the schema to project, in the order they appear in the
+    /// output. `None` means all columns are projected.
+    pub projection: Option<Vec<usize>>,
+    /// The maximum number of records to read from this scan, `None` means no
+    /// limit.
+    pub limit: Option<usize>,
+    /// Partition columns that are implied by a file's location rather than
+    /// stored in the file itself (e.g. Hive-style partitioning).
+    pub table_partition_cols: Vec<Field>,
+}
+
+impl FileScanConfig {
+    /// Project the file schema with `self.projection`, appending the
+    /// partition columns, and return the resulting schema together with the
+    /// projected statistics.
+    pub fn project(&self) -> (SchemaRef, Statistics) {
+        if self.file_schema.fields().is_empty() && self.table_partition_cols.is_empty() {
+            return (self.file_schema.clone(), self.statistics.clone());
+        }
+
+        let proj_iter: Box<dyn Iterator<Item = usize>> = match &self.projection {
+            Some(range) => Box::new(range.iter().copied()),
+            None => Box::new(0..self.file_schema.fields().len() + self.table_partition_cols.len()),
+        };
+
+        let mut table_fields = vec![];
+        let mut table_cols_stats = vec![];
+        for idx in proj_iter {
+            if idx < self.file_schema.fields().len() {
+                table_fields.push(self.file_schema.field(idx).clone());
+                table_cols_stats.push(
+                    self.statistics
+                        .column_statistics
+                        .as_ref()
+                        .map(|stats| stats[idx].clone())
+                        .unwrap_or_default(),
+                );
+            } else {
+                let partition_idx = idx - self.file_schema.fields().len();
+                table_fields.push(self.table_partition_cols[partition_idx].clone());
+                // Statistics for partition columns are not tracked here.
+                table_cols_stats.push(ColumnStatistics::default());
+            }
+        }
+
+        let table_stats = Statistics {
+            num_rows: self.statistics.num_rows,
+            is_exact: self.statistics.is_exact,
+            total_byte_size: None,
+            column_statistics: Some(table_cols_stats),
+        };
+
+        (
+            Arc::new(Schema::new(table_fields)),
+            table_stats,
+        )
+    }
+}
+
+/// Projects partition columns into a [`RecordBatch`] returned by a file
+/// reader, so downstream operators see them as ordinary columns.
+pub struct PartitionColumnProjector {
+    /// Schema of the projected output, including partition columns.
+    projected_schema: SchemaRef,
+    /// `(partition_idx, projected_idx)` for each projected partition column:
+    /// its index in [`FileScanConfig::table_partition_cols`] (and so in a
+    /// file's `partition_values`) and its index within `projected_schema`,
+    /// sorted by `projected_idx`.
+    projected_partition_indexes: Vec<(usize, usize)>,
+}
+
+impl PartitionColumnProjector {
+    pub fn new(projected_schema: SchemaRef, table_partition_cols: &[Field]) -> Self {
+        let mut projected_partition_indexes = Vec::with_capacity(table_partition_cols.len());
+        for (partition_idx, partition_field) in table_partition_cols.iter().enumerate() {
+            if let Ok(projected_idx) = projected_schema.index_of(partition_field.name()) {
+                projected_partition_indexes.push((partition_idx, projected_idx));
+            }
+        }
+        projected_partition_indexes.sort_by_key(|(_, projected_idx)| *projected_idx);
+
+        Self {
+            projected_schema,
+            projected_partition_indexes,
+        }
+    }
+
+    /// The schema a file itself must produce before partition columns are
+    /// spliced in by [`Self::project`], i.e. `projected_schema` with the
+    /// partition columns removed.
+    pub fn file_schema(&self) -> SchemaRef {
+        if self.projected_partition_indexes.is_empty() {
+            return self.projected_schema.clone();
+        }
+
+        let fields = self
+            .projected_schema
+            .fields()
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| {
+                !self
+                    .projected_partition_indexes
+                    .iter()
+                    .any(|(_, projected_idx)| projected_idx == idx)
+            })
+            .map(|(_, field)| field.clone())
+            .collect::<Vec<_>>();
+
+        Arc::new(Schema::new(fields))
+    }
+
+    /// Splice `partition_values` into `file_batch` at the positions the
+    /// partition columns occupy in the projected schema.
+    pub fn project(
+        &mut self,
+        file_batch: RecordBatch,
+        partition_values: &[ScalarValue],
+    ) -> ArrowResult<RecordBatch> {
+        if self.projected_partition_indexes.is_empty() {
+            return Ok(file_batch);
+        }
+
+        let mut cols = file_batch.columns().to_vec();
+        for (partition_idx, projected_idx) in &self.projected_partition_indexes {
+            let partition_value = partition_values.get(*partition_idx).ok_or_else(|| {
+                DataFusionError::Execution(
+                    "Missing partition column value while projecting file batch".to_string(),
+                )
+            })?;
+            cols.insert(
+                *projected_idx,
+                partition_value.to_array_of_size(file_batch.num_rows()),
+            );
+        }
+
+        Ok(RecordBatch::try_new(self.projected_schema.clone(), cols)?)
+    }
+}
@@ -0,0 +1,221 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Reconciles a file's physical schema with the table's projected schema, so
+//! a single scan can span files whose schema has drifted (a new/renamed
+//! column, or a type promoted by a newer writer) without failing the query.
+
+use datafusion::arrow::array::{new_null_array, ArrayRef};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, SchemaRef};
+use datafusion::arrow::error::{ArrowError, Result as ArrowResult};
+use datafusion::arrow::record_batch::RecordBatch;
+
+/// Sentinel source index meaning "this output column is absent from the
+/// file"; the batch column is synthesized as an all-null array instead of
+/// being read.
+const MISSING_COLUMN: usize = usize::MAX;
+
+/// Adapts record batches read from a file to the schema the scan as a whole
+/// must produce, casting or null-filling columns as needed.
+///
+/// The mapping from file columns to output columns is resolved once, from
+/// the first batch a file produces, and then reused for every subsequent
+/// batch of that file.
+pub struct SchemaAdapter {
+    /// The schema every adapted batch must conform to.
+    projected_schema: SchemaRef,
+    /// `mapping[i]` describes how to produce output column `i`:
+    /// `(source_index, None)` copies the file column as-is, `(source_index,
+    /// Some(ty))` casts it to `ty`, and `(MISSING_COLUMN, Some(ty))`
+    /// synthesizes a null array of `ty`.
+    mapping: Option<Vec<(usize, Option<DataType>)>>,
+}
+
+impl SchemaAdapter {
+    pub fn new(projected_schema: SchemaRef) -> Self {
+        Self {
+            projected_schema,
+            mapping: None,
+        }
+    }
+
+    /// Cast/null-fill `batch` into `self.projected_schema`, building the
+    /// column mapping from `batch`'s schema the first time this is called.
+    pub fn adapt(&mut self, batch: RecordBatch) -> ArrowResult<RecordBatch> {
+        if self.mapping.is_none() {
+            self.mapping = Some(Self::build_mapping(&self.projected_schema, &batch)?);
+        }
+        let mapping = self.mapping.as_ref().unwrap();
+
+        let num_rows = batch.num_rows();
+        let columns = mapping
+            .iter()
+            .zip(self.projected_schema.fields())
+            .map(|((source_index, cast_to), field)| -> ArrowResult<ArrayRef> {
+                if *source_index == MISSING_COLUMN {
+                    return Ok(new_null_array(field.data_type(), num_rows));
+                }
+                let array = batch.column(*source_index).clone();
+                match cast_to {
+                    Some(target_type) => cast(&array, target_type),
+                    None => Ok(array),
+                }
+            })
+            .collect::<ArrowResult<Vec<_>>>()?;
+
+        RecordBatch::try_new(self.projected_schema.clone(), columns)
+    }
+
+    fn build_mapping(
+        projected_schema: &SchemaRef,
+        batch: &RecordBatch,
+    ) -> ArrowResult<Vec<(usize, Option<DataType>)>> {
+        let file_schema = batch.schema();
+
+        projected_schema
+            .fields()
+            .iter()
+            .map(|field| match file_schema.index_of(field.name()) {
+                Ok(source_index) => {
+                    let source_type = file_schema.field(source_index).data_type();
+                    if source_type == field.data_type() {
+                        Ok((source_index, None))
+                    } else if can_cast_types(source_type, field.data_type()) {
+                        Ok((source_index, Some(field.data_type().clone())))
+                    } else {
+                        Err(ArrowError::SchemaError(format!(
+                            "cannot reconcile file column \"{}\" of type {source_type:?} \
+                             with table column type {:?}: unsupported cast",
+                            field.name(),
+                            field.data_type()
+                        )))
+                    }
+                }
+                Err(_) => Ok((MISSING_COLUMN, Some(field.data_type().clone()))),
+            })
+            .collect()
+    }
+}
+
+fn can_cast_types(from: &DataType, to: &DataType) -> bool {
+    datafusion::arrow::compute::can_cast_types(from, to)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::{Int32Array, Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{Field, Schema};
+
+    use super::*;
+
+    #[test]
+    fn passthrough_when_schemas_match() {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, true)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3])) as ArrayRef],
+        )
+        .unwrap();
+
+        let mut adapter = SchemaAdapter::new(schema.clone());
+        let adapted = adapter.adapt(batch).unwrap();
+        assert_eq!(adapted.schema(), schema);
+        assert_eq!(
+            adapted
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int32Array>()
+                .unwrap(),
+            &Int32Array::from(vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn casts_widened_column() {
+        let projected_schema =
+            Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, true)]));
+        let file_schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, true)]));
+        let batch = RecordBatch::try_new(
+            file_schema,
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3])) as ArrayRef],
+        )
+        .unwrap();
+
+        let mut adapter = SchemaAdapter::new(projected_schema);
+        let adapted = adapter.adapt(batch).unwrap();
+        assert_eq!(
+            adapted
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap(),
+            &Int64Array::from(vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn null_fills_missing_column() {
+        let projected_schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Int32, true),
+            Field::new("b", DataType::Utf8, true),
+        ]));
+        let file_schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, true)]));
+        let batch = RecordBatch::try_new(
+            file_schema,
+            vec![Arc::new(Int32Array::from(vec![1, 2])) as ArrayRef],
+        )
+        .unwrap();
+
+        let mut adapter = SchemaAdapter::new(projected_schema);
+        let adapted = adapter.adapt(batch).unwrap();
+        let b = adapted
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(b.len(), 2);
+        assert!(b.is_null(0) && b.is_null(1));
+    }
+
+    #[test]
+    fn errors_on_unsupported_cast() {
+        let projected_schema = Arc::new(Schema::new(vec![Field::new(
+            "a",
+            DataType::Binary,
+            true,
+        )]));
+        let file_schema = Arc::new(Schema::new(vec![Field::new(
+            "a",
+            DataType::Boolean,
+            true,
+        )]));
+        let batch = RecordBatch::try_new(
+            file_schema,
+            vec![Arc::new(datafusion::arrow::array::BooleanArray::from(vec![
+                true, false,
+            ])) as ArrayRef],
+        )
+        .unwrap();
+
+        let mut adapter = SchemaAdapter::new(projected_schema);
+        let err = adapter.adapt(batch).unwrap_err();
+        assert!(matches!(err, ArrowError::SchemaError(_)));
+    }
+}
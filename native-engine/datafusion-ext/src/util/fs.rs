@@ -0,0 +1,89 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! A thin, pluggable abstraction over the object store(s) backing a scan, so
+//! the native engine can talk to HDFS, S3-compatible stores, or the local
+//! filesystem through a single handle threaded down to every [`FormatReader`].
+//!
+//! [`FormatReader`]: crate::file_format::file_stream::FormatReader
+
+use std::any::Any;
+use std::future::Future;
+use std::sync::Arc;
+
+use datafusion::error::Result;
+use object_store::{ObjectMeta, ObjectStore};
+
+use crate::util::metadata_cache::{CachedMetadata, MetadataCache};
+
+/// Default byte budget for [`FsProvider`]'s shared metadata cache: 64 MiB.
+const DEFAULT_METADATA_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/// A handle to the object store(s) a task should use to resolve file paths.
+///
+/// Also owns a shared cache of per-file metadata (e.g. parsed footers), so
+/// [`FormatReader`] implementations and repeated scans of the same file don't
+/// each re-fetch it from the object store.
+///
+/// [`FormatReader`]: crate::file_format::file_stream::FormatReader
+pub struct FsProvider {
+    store: Arc<dyn ObjectStore>,
+    metadata_cache: MetadataCache,
+}
+
+impl FsProvider {
+    pub fn new(store: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            store,
+            metadata_cache: MetadataCache::new(DEFAULT_METADATA_CACHE_BYTES),
+        }
+    }
+
+    /// Override the byte budget of the shared metadata cache.
+    pub fn with_metadata_cache_bytes(mut self, budget_bytes: usize) -> Self {
+        self.metadata_cache = MetadataCache::new(budget_bytes);
+        self
+    }
+
+    pub fn object_store(&self) -> &Arc<dyn ObjectStore> {
+        &self.store
+    }
+
+    /// Return the cached metadata for `object_meta`, calling `fetch_fn` to
+    /// compute and cache it on a miss. `size_hint` is the byte cost charged
+    /// against the cache's budget for the fetched value; a stale entry (one
+    /// whose file has since changed size or modification time) is treated as
+    /// a miss, since the cache key is `(path, size, last_modified)`.
+    ///
+    /// Concurrent callers racing on the same `object_meta` share a single
+    /// `fetch_fn` call rather than each missing the cache and fetching it
+    /// themselves; see [`MetadataCache::get_or_fetch_with`].
+    pub async fn get_or_fetch_metadata<F, Fut>(
+        &self,
+        object_meta: &ObjectMeta,
+        size_hint: usize,
+        fetch_fn: F,
+    ) -> Result<CachedMetadata>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Arc<dyn Any + Send + Sync>>>,
+    {
+        self.metadata_cache
+            .get_or_fetch_with(object_meta, size_hint, fetch_fn)
+            .await
+    }
+}
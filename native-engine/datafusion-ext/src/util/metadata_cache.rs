@@ -0,0 +1,341 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! A bounded, byte-budgeted LRU cache of per-file metadata (trailing bytes or
+//! already-parsed format metadata), so repeated scans of the same file don't
+//! re-fetch its footer. Entries are keyed by `(path, size, last_modified)`,
+//! so a file replaced at the same path invalidates automatically.
+
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use datafusion::error::Result;
+use object_store::path::Path;
+use object_store::ObjectMeta;
+use tokio::sync::Mutex as AsyncMutex;
+
+/// Opaque, format-specific metadata stashed in the cache (e.g. a parsed
+/// Parquet footer).
+pub type CachedMetadata = Arc<dyn Any + Send + Sync>;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    path: Path,
+    size: usize,
+    last_modified: DateTime<Utc>,
+}
+
+impl From<&ObjectMeta> for CacheKey {
+    fn from(object_meta: &ObjectMeta) -> Self {
+        Self {
+            path: object_meta.location.clone(),
+            size: object_meta.size,
+            last_modified: object_meta.last_modified,
+        }
+    }
+}
+
+struct CacheEntry {
+    value: CachedMetadata,
+    /// Byte cost charged against the cache's budget for this entry, as
+    /// reported by the caller when it was inserted.
+    cost: usize,
+}
+
+struct Inner {
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Least-recently-used ordering, front is least recently used.
+    recency: VecDeque<CacheKey>,
+    used_bytes: usize,
+}
+
+/// A bounded LRU cache of [`CachedMetadata`], evicting the least recently
+/// used entries once `budget_bytes` would be exceeded.
+pub struct MetadataCache {
+    budget_bytes: usize,
+    inner: Mutex<Inner>,
+    /// Per-key locks de-duplicating concurrent fetches of the same file's
+    /// metadata, so that partitions racing to open the same file only run
+    /// the caller's fetch once instead of once per racer. See
+    /// [`Self::get_or_fetch_with`].
+    in_flight: Mutex<HashMap<CacheKey, Arc<AsyncMutex<()>>>>,
+}
+
+impl MetadataCache {
+    pub fn new(budget_bytes: usize) -> Self {
+        Self {
+            budget_bytes,
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                recency: VecDeque::new(),
+                used_bytes: 0,
+            }),
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Return the cached metadata for `object_meta` if present and still
+    /// valid (its size/last-modified still match what was cached).
+    pub fn get(&self, object_meta: &ObjectMeta) -> Option<CachedMetadata> {
+        let key = CacheKey::from(object_meta);
+        let mut inner = self.inner.lock().unwrap();
+        let value = inner.entries.get(&key)?.value.clone();
+        inner.touch(&key);
+        Some(value)
+    }
+
+    /// Insert `value` for `object_meta`, charging `cost` bytes against the
+    /// budget and evicting the least-recently-used entries as needed to make
+    /// room.
+    pub fn insert(&self, object_meta: &ObjectMeta, value: CachedMetadata, cost: usize) {
+        let key = CacheKey::from(object_meta);
+        let mut inner = self.inner.lock().unwrap();
+        inner.insert(key, CacheEntry { value, cost }, self.budget_bytes);
+    }
+
+    /// Like [`Self::get`] followed by [`Self::insert`] on a miss, except
+    /// concurrent callers for the same `object_meta` share a single
+    /// in-flight `fetch_fn` call instead of each running their own: the
+    /// first caller to miss runs `fetch_fn` and populates the cache, and
+    /// every other caller racing it waits on the same file's lock and then
+    /// re-checks the cache, rather than also missing and re-fetching.
+    pub async fn get_or_fetch_with<F, Fut>(
+        &self,
+        object_meta: &ObjectMeta,
+        cost: usize,
+        fetch_fn: F,
+    ) -> Result<CachedMetadata>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<CachedMetadata>>,
+    {
+        if let Some(value) = self.get(object_meta) {
+            return Ok(value);
+        }
+
+        let key = CacheKey::from(object_meta);
+        let key_lock = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            in_flight
+                .entry(key.clone())
+                .or_insert_with(|| Arc::new(AsyncMutex::new(())))
+                .clone()
+        };
+
+        let _guard = key_lock.lock().await;
+        let result = (|| async {
+            // A racing caller may have already fetched and inserted this key
+            // while we were waiting for `key_lock`.
+            if let Some(value) = self.get(object_meta) {
+                return Ok(value);
+            }
+
+            let value = fetch_fn().await?;
+            self.insert(object_meta, value.clone(), cost);
+            Ok(value)
+        })()
+        .await;
+
+        // Drop the now-idle per-key lock once we're its only holder besides
+        // the map entry, so `in_flight` doesn't grow without bound.
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if matches!(in_flight.get(&key), Some(lock) if Arc::ptr_eq(lock, &key_lock))
+            && Arc::strong_count(&key_lock) == 2
+        {
+            in_flight.remove(&key);
+        }
+
+        result
+    }
+}
+
+impl Inner {
+    fn touch(&mut self, key: &CacheKey) {
+        if let Some(pos) = self.recency.iter().position(|k| k == key) {
+            let key = self.recency.remove(pos).unwrap();
+            self.recency.push_back(key);
+        }
+    }
+
+    fn insert(&mut self, key: CacheKey, entry: CacheEntry, budget_bytes: usize) {
+        if let Some(old) = self.entries.remove(&key) {
+            self.used_bytes -= old.cost;
+            self.recency.retain(|k| k != &key);
+        }
+
+        while self.used_bytes + entry.cost > budget_bytes {
+            let Some(lru_key) = self.recency.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&lru_key) {
+                self.used_bytes -= evicted.cost;
+            }
+        }
+
+        self.used_bytes += entry.cost;
+        self.recency.push_back(key.clone());
+        self.entries.insert(key, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn object_meta(path: &str, size: usize, last_modified_secs: i64) -> ObjectMeta {
+        ObjectMeta {
+            location: Path::from(path),
+            last_modified: Utc.timestamp_opt(last_modified_secs, 0).unwrap(),
+            size,
+        }
+    }
+
+    fn value(n: u8) -> CachedMetadata {
+        Arc::new(n)
+    }
+
+    #[test]
+    fn hit_and_miss() {
+        let cache = MetadataCache::new(1024);
+        let meta = object_meta("a", 10, 0);
+
+        assert!(cache.get(&meta).is_none());
+        cache.insert(&meta, value(1), 100);
+        let cached = cache.get(&meta).unwrap();
+        assert_eq!(*cached.downcast_ref::<u8>().unwrap(), 1);
+    }
+
+    #[test]
+    fn stale_size_invalidates_entry() {
+        let cache = MetadataCache::new(1024);
+        let meta = object_meta("a", 10, 0);
+        cache.insert(&meta, value(1), 100);
+
+        let resized = object_meta("a", 20, 0);
+        assert!(cache.get(&resized).is_none());
+    }
+
+    #[test]
+    fn stale_last_modified_invalidates_entry() {
+        let cache = MetadataCache::new(1024);
+        let meta = object_meta("a", 10, 0);
+        cache.insert(&meta, value(1), 100);
+
+        let touched = object_meta("a", 10, 1);
+        assert!(cache.get(&touched).is_none());
+    }
+
+    #[test]
+    fn eviction_respects_byte_budget() {
+        let cache = MetadataCache::new(150);
+        let a = object_meta("a", 10, 0);
+        let b = object_meta("b", 10, 0);
+        let c = object_meta("c", 10, 0);
+
+        cache.insert(&a, value(1), 100);
+        cache.insert(&b, value(2), 100);
+
+        // Inserting b should have evicted a to stay within budget.
+        assert!(cache.get(&a).is_none());
+        assert!(cache.get(&b).is_some());
+
+        cache.insert(&c, value(3), 100);
+        assert!(cache.get(&b).is_none());
+        assert!(cache.get(&c).is_some());
+    }
+
+    #[test]
+    fn recently_used_entry_survives_eviction() {
+        let cache = MetadataCache::new(200);
+        let a = object_meta("a", 10, 0);
+        let b = object_meta("b", 10, 0);
+        let c = object_meta("c", 10, 0);
+
+        cache.insert(&a, value(1), 100);
+        cache.insert(&b, value(2), 40);
+        // Touch `a` so `b` becomes the least-recently-used entry.
+        assert!(cache.get(&a).is_some());
+
+        cache.insert(&c, value(3), 100);
+        assert!(cache.get(&a).is_some());
+        assert!(cache.get(&b).is_none());
+        assert!(cache.get(&c).is_some());
+    }
+
+    #[tokio::test]
+    async fn concurrent_fetches_for_the_same_key_are_deduplicated() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let cache = Arc::new(MetadataCache::new(1024));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let first_started = Arc::new(tokio::sync::Notify::new());
+        let let_first_finish = Arc::new(tokio::sync::Notify::new());
+
+        let first = tokio::spawn({
+            let cache = cache.clone();
+            let calls = calls.clone();
+            let first_started = first_started.clone();
+            let let_first_finish = let_first_finish.clone();
+            async move {
+                cache
+                    .get_or_fetch_with(&object_meta("a", 10, 0), 100, || async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        first_started.notify_one();
+                        let_first_finish.notified().await;
+                        Ok(value(1))
+                    })
+                    .await
+            }
+        });
+
+        // Wait for the first fetch to be under way before racing the second.
+        first_started.notified().await;
+
+        let second = tokio::spawn({
+            let cache = cache.clone();
+            let calls = calls.clone();
+            async move {
+                cache
+                    .get_or_fetch_with(&object_meta("a", 10, 0), 100, || async move {
+                        // Should never run: the first fetch's result is
+                        // shared instead of re-fetched.
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        Ok(value(2))
+                    })
+                    .await
+            }
+        });
+
+        // Give `second` a chance to reach and block on the same in-flight
+        // lock `first` is holding, then let `first` complete.
+        tokio::task::yield_now().await;
+        let_first_finish.notify_one();
+
+        let (first_result, second_result) = tokio::join!(first, second);
+        let first_value = first_result.unwrap().unwrap();
+        let second_value = second_result.unwrap().unwrap();
+
+        assert_eq!(*first_value.downcast_ref::<u8>().unwrap(), 1);
+        assert_eq!(*second_value.downcast_ref::<u8>().unwrap(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}